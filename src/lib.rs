@@ -3,7 +3,9 @@
 //! or spaced-out with newlines between every single element (bad for interchange, and only barely human-readable).
 //!
 //! This crate provides a middle ground: the overarching structure is formatted like `PrettyFormatter`,
-//! but lists and objects consisting entirely of primitive values are formatted on a single line (but still not as densely as `CompactFormatter`).
+//! but lists and objects whose entire contents fit on one line (primitives, or nested lists and
+//! objects that are themselves simple enough to collapse) are formatted on a single line instead
+//! (but still not as densely as `CompactFormatter`).
 //! The result looks something like this:
 //!
 //! ```json
@@ -40,6 +42,7 @@
 use serde::Serialize;
 use serde_json::{ser::CompactFormatter as CF, Serializer};
 use std::io::Write;
+use std::rc::Rc;
 
 type Result<T = (), E = std::io::Error> = std::result::Result<T, E>;
 
@@ -64,14 +67,87 @@ impl<A: Write, B: Write> Write for Either<A, B> {
 	}
 }
 
+/// Wraps a writer, counting the bytes written through it.
+struct CountingWriter<W> {
+	inner: W,
+	count: usize,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+	fn write(&mut self, buf: &[u8]) -> Result<usize> {
+		let n = self.inner.write(buf)?;
+		self.count += n;
+		Ok(n)
+	}
+
+	fn flush(&mut self) -> Result {
+		self.inner.flush()
+	}
+}
+
+/// Adapts a [`std::fmt::Write`] sink so it can be used as an [`std::io::Write`] target.
+struct FmtWriter<'a, W: std::fmt::Write + ?Sized>(&'a mut W);
+
+impl<W: std::fmt::Write + ?Sized> Write for FmtWriter<'_, W> {
+	fn write(&mut self, buf: &[u8]) -> Result<usize> {
+		// SAFETY: this formatter only ever feeds valid UTF-8 to its writer (see `to_writer`).
+		let s = unsafe { std::str::from_utf8_unchecked(buf) };
+		self.0
+			.write_str(s)
+			.map_err(|_| std::io::Error::other("formatter error"))?;
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> Result {
+		Ok(())
+	}
+}
+
+/// Whether a container being considered for collapsing is an array or an object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContainerKind {
+	Array,
+	Object,
+}
+
+/// Information passed to a [`Formatter::with_policy`] predicate, describing a buffered container
+/// that is a candidate for being collapsed onto a single line.
+#[derive(Clone, Copy, Debug)]
+pub struct CollapseContext {
+	/// Whether the container is an array or an object.
+	pub kind: ContainerKind,
+	/// The number of direct children the container has.
+	pub len: usize,
+	/// The nesting depth of the container, i.e. `current_indent` at the point it would be written.
+	pub depth: usize,
+	/// The number of bytes the container would occupy if collapsed onto one line.
+	pub inline_len: usize,
+}
+
+/// A `Fn(CollapseContext) -> bool` collapse policy, wrapped so `Formatter` can stay `Clone` and
+/// `Debug` despite holding one.
+#[derive(Clone)]
+struct Policy<'a>(Rc<dyn Fn(CollapseContext) -> bool + 'a>);
+
+impl std::fmt::Debug for Policy<'_> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_tuple("Policy").finish()
+	}
+}
+
 /// A pretty-printer that saves vertical space on lists of primitive values.
 ///
 /// See module-level documentation for more information.
 #[derive(Clone, Debug)]
 pub struct Formatter<'a> {
 	current_indent: usize,
-	buffer: Option<Vec<Vec<u8>>>,
+	current_column: usize,
+	columns: Vec<usize>,
+	buffers: Vec<Vec<Vec<u8>>>,
 	indent: &'a [u8],
+	max_width: usize,
+	policy: Option<Policy<'a>>,
+	reindent_raw_values: bool,
 }
 
 impl<'a> Formatter<'a> {
@@ -84,10 +160,56 @@ impl<'a> Formatter<'a> {
 	pub fn with_indent(indent: &'a [u8]) -> Self {
 		Formatter {
 			current_indent: 0,
-			buffer: None,
+			current_column: 0,
+			columns: Vec::new(),
+			buffers: Vec::new(),
 			indent,
+			max_width: 0,
+			policy: None,
+			reindent_raw_values: false,
 		}
 	}
+
+	/// Only collapse a container onto a single line if doing so keeps it within `max_width`
+	/// columns; otherwise fall back to one element per line, like `PrettyFormatter`.
+	///
+	/// A `max_width` of `0` (the default) disables the check, so every container whose children
+	/// are each on a single line is collapsed.
+	///
+	/// When a nested container is checked, it is not yet known whether the containers around it
+	/// collapse, so its starting column is taken as the larger of the two possibilities. A
+	/// container may therefore be spread out even though it would have fit.
+	///
+	/// Ignored once [`Formatter::with_policy`] is used, which takes over the collapse decision
+	/// entirely.
+	pub fn with_max_width(mut self, max_width: usize) -> Self {
+		self.max_width = max_width;
+		self
+	}
+
+	/// Decide whether to collapse a container onto a single line with a custom `policy`,
+	/// instead of the default max-width-based rule.
+	///
+	/// For example, `|ctx| ctx.kind == ContainerKind::Array` only ever collapses arrays, never
+	/// objects, and `|ctx| ctx.depth >= 2` only collapses containers nested at least two levels
+	/// deep. A container is still never collapsed if one of its children wasn't, regardless of
+	/// what `policy` returns for it.
+	pub fn with_policy(mut self, policy: impl Fn(CollapseContext) -> bool + 'a) -> Self {
+		self.policy = Some(Policy(Rc::new(policy)));
+		self
+	}
+
+	/// Re-indent embedded [`serde_json::value::RawValue`] fragments instead of forwarding them
+	/// verbatim.
+	///
+	/// Without this, pre-serialized fragments are stitched in compact, breaking the alignment of
+	/// otherwise nicely formatted output. With it, a fragment is parsed and re-emitted through
+	/// this same `Formatter`, so it obeys the crate's collapsing and indentation rules like
+	/// everything else.
+	pub fn with_reindent_raw_values(mut self, reindent_raw_values: bool) -> Self {
+		self.reindent_raw_values = reindent_raw_values;
+		self
+	}
 }
 
 impl Default for Formatter<'_> {
@@ -101,82 +223,256 @@ impl Formatter<'_> {
 		&'a mut self,
 		w: &'b mut W,
 	) -> Either<&'a mut Vec<u8>, &'b mut W> {
-		if let Some(buf) = &mut self.buffer {
+		if let Some(buf) = self.buffers.last_mut() {
 			Either::A(buf.last_mut().unwrap())
 		} else {
 			Either::B(w)
 		}
 	}
 
-	fn begin<W: Write + ?Sized>(&mut self, w: &mut W, bytes: &[u8]) -> Result {
+	/// Write `bytes` through `self.writer(w)`, bumping `current_column` regardless of whether
+	/// they land on the real writer or a buffer, so a buffered container's descendants still see
+	/// a column to check `max_width` against (e.g. for an object value, the column right after
+	/// its key).
+	fn write_tracked<W: Write + ?Sized>(&mut self, w: &mut W, bytes: &[u8]) -> Result {
 		self.writer(w).write_all(bytes)?;
-		if let Some(buf) = self.buffer.replace(Vec::new()) {
-			let mut first = Some(());
-			for val in buf {
-				if first.take().is_none() {
-					w.write_all(b",")?;
-				}
-				self.indent(w)?;
-				w.write_all(&val)?;
-			}
-		}
+		self.current_column += bytes.len();
+		Ok(())
+	}
+
+	fn begin<W: Write + ?Sized>(&mut self, w: &mut W, bytes: &[u8]) -> Result {
+		self.columns.push(self.current_column);
+		self.write_tracked(w, bytes)?;
+		self.buffers.push(Vec::new());
 		self.current_indent += 1;
 		Ok(())
 	}
 
-	fn end<W: Write + ?Sized>(&mut self, w: &mut W, bytes: &[u8]) -> Result {
+	fn end<W: Write + ?Sized>(&mut self, w: &mut W, bytes: &[u8], kind: ContainerKind) -> Result {
+		let column_at_open = self.columns.pop().unwrap();
 		self.current_indent -= 1;
-		if let Some(buf) = self.buffer.take() {
-			if !buf.is_empty() {
+		let buf = self.buffers.pop().unwrap();
+		if !buf.is_empty() {
+			// Eligible to collapse onto one line only if every child is itself already a single
+			// line (a scalar, or a nested container that collapsed) and the policy allows it (by
+			// default: the result fits within `max_width`).
+			let simple = buf.iter().all(|val| !val.contains(&b'\n'));
+			// "[ " + children joined by ", " + " ]"
+			let inline_len = 4 + buf.iter().map(Vec::len).sum::<usize>() + 2 * (buf.len() - 1);
+			let collapse = simple
+				&& match &self.policy {
+					Some(policy) => policy.0(CollapseContext {
+						kind,
+						len: buf.len(),
+						depth: self.current_indent,
+						inline_len,
+					}),
+					None => self.max_width == 0 || column_at_open + inline_len <= self.max_width,
+				};
+			if collapse {
 				let mut first = Some(());
 				for val in buf {
 					if first.take().is_some() {
-						w.write_all(b" ")?;
+						self.write_tracked(w, b" ")?;
 					} else {
-						w.write_all(b", ")?;
+						self.write_tracked(w, b", ")?;
+					}
+					self.write_tracked(w, &val)?;
+				}
+				self.write_tracked(w, b" ")?;
+				// The children were already counted while buffered; count the collapsed line once,
+				// up to the closing bracket written below.
+				self.current_column = column_at_open + inline_len - 1;
+			} else {
+				self.current_indent += 1;
+				for (i, val) in buf.into_iter().enumerate() {
+					if i > 0 {
+						self.write_tracked(w, b",")?;
 					}
-					w.write_all(&val)?;
+					self.indent(w)?;
+					self.write_tracked(w, &val)?;
 				}
-				w.write_all(b" ")?;
+				self.current_indent -= 1;
+				self.indent(w)?;
 			}
-		} else {
-			self.indent(w)?;
 		}
-		w.write_all(bytes)?;
+		self.write_tracked(w, bytes)?;
 		if self.current_indent == 0 {
 			self.indent(w)?;
 		}
 		Ok(())
 	}
 
-	fn value<W: Write + ?Sized>(&mut self, w: &mut W, first: bool) -> Result {
-		if let Some(buf) = &mut self.buffer {
-			buf.push(Vec::new())
-		} else if !first {
-			w.write_all(b",")?;
-			self.indent(w)?;
-		}
+	fn value<W: Write + ?Sized>(&mut self, _w: &mut W, first: bool) -> Result {
+		self.buffers.last_mut().unwrap().push(Vec::new());
+		// The child starts after "[ " or ", " if this container collapses, or after the
+		// indentation if it doesn't; take the larger so width checks never undershoot.
+		let inline = self.current_column + if first { 1 } else { 2 };
+		self.current_column = inline.max(self.current_indent * self.indent.len());
 		Ok(())
 	}
 
 	fn indent<W: Write + ?Sized>(&mut self, w: &mut W) -> Result {
-		w.write_all(b"\n")?;
+		self.write_tracked(w, b"\n")?;
+		let indent = self.indent;
 		for _ in 0..self.current_indent {
-			w.write_all(self.indent)?;
+			self.write_tracked(w, indent)?;
 		}
+		self.current_column = self.current_indent * self.indent.len();
 		Ok(())
 	}
+
+	/// Re-emit a raw JSON fragment through `self`, walking it with a small streaming scanner
+	/// rather than materializing it into a `serde_json::Value`, so it goes through the usual
+	/// `begin`/`value`/`end` collapsing machinery without reordering object keys (`Value::Object`
+	/// is a `BTreeMap` without the `preserve_order` feature) or rewriting numeric literals
+	/// (`Value::Number` normalizes e.g. `1.50` to `1.5`). Scalars are forwarded byte-for-byte.
+	fn write_raw_json<W: Write + ?Sized>(&mut self, w: &mut W, fragment: &str) -> Result {
+		let bytes = fragment.as_bytes();
+		let mut i = 0;
+		skip_ws(bytes, &mut i);
+		self.write_raw_json_value(w, bytes, &mut i)
+	}
+
+	fn write_raw_json_value<W: Write + ?Sized>(
+		&mut self,
+		w: &mut W,
+		bytes: &[u8],
+		i: &mut usize,
+	) -> Result {
+		match bytes.get(*i) {
+			Some(b'{') => self.write_raw_json_container(w, bytes, i, b'{', b'}', ContainerKind::Object),
+			Some(b'[') => self.write_raw_json_container(w, bytes, i, b'[', b']', ContainerKind::Array),
+			Some(b'"') => {
+				let span = scan_string(bytes, i)?;
+				self.write_tracked(w, span)
+			}
+			Some(_) => {
+				let span = scan_literal(bytes, i)?;
+				self.write_tracked(w, span)
+			}
+			None => Err(raw_json_error("unexpected end of raw JSON fragment")),
+		}
+	}
+
+	fn write_raw_json_container<W: Write + ?Sized>(
+		&mut self,
+		w: &mut W,
+		bytes: &[u8],
+		i: &mut usize,
+		open: u8,
+		close: u8,
+		kind: ContainerKind,
+	) -> Result {
+		self.begin(w, &[open])?;
+		*i += 1;
+		skip_ws(bytes, i);
+		let mut first = true;
+		while bytes.get(*i) != Some(&close) {
+			self.value(w, first)?;
+			first = false;
+			if kind == ContainerKind::Object {
+				let key = scan_string(bytes, i)?;
+				self.write_tracked(w, key)?;
+				skip_ws(bytes, i);
+				expect_byte(bytes, i, b':')?;
+				skip_ws(bytes, i);
+				self.write_tracked(w, b": ")?;
+			}
+			self.write_raw_json_value(w, bytes, i)?;
+			skip_ws(bytes, i);
+			if bytes.get(*i) == Some(&b',') {
+				*i += 1;
+				skip_ws(bytes, i);
+			} else if bytes.get(*i) != Some(&close) {
+				return Err(raw_json_error("expected ',' or closing bracket in raw JSON fragment"));
+			}
+		}
+		expect_byte(bytes, i, close)?;
+		self.end(w, &[close], kind)
+	}
+}
+
+fn raw_json_error(message: &str) -> std::io::Error {
+	std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_owned())
+}
+
+fn skip_ws(bytes: &[u8], i: &mut usize) {
+	while matches!(bytes.get(*i), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+		*i += 1;
+	}
+}
+
+fn expect_byte(bytes: &[u8], i: &mut usize, expected: u8) -> Result<()> {
+	if bytes.get(*i) == Some(&expected) {
+		*i += 1;
+		Ok(())
+	} else {
+		Err(raw_json_error(&format!(
+			"expected {:?} in raw JSON fragment",
+			expected as char
+		)))
+	}
+}
+
+/// Scan a `"..."` string literal, honoring (but not interpreting) backslash escapes, and return
+/// it verbatim including the surrounding quotes.
+fn scan_string<'a>(bytes: &'a [u8], i: &mut usize) -> Result<&'a [u8]> {
+	let start = *i;
+	expect_byte(bytes, i, b'"')?;
+	loop {
+		match bytes.get(*i) {
+			Some(b'\\') => *i += 2,
+			Some(b'"') => {
+				*i += 1;
+				break;
+			}
+			Some(_) => *i += 1,
+			None => return Err(raw_json_error("unterminated string in raw JSON fragment")),
+		}
+	}
+	Ok(&bytes[start..*i])
+}
+
+/// Scan a bare literal (`true`, `false`, `null`, or a number) up to the next structural
+/// character, and return it verbatim.
+fn scan_literal<'a>(bytes: &'a [u8], i: &mut usize) -> Result<&'a [u8]> {
+	let start = *i;
+	while let Some(&b) = bytes.get(*i) {
+		if matches!(b, b',' | b']' | b'}' | b':' | b' ' | b'\t' | b'\n' | b'\r') {
+			break;
+		}
+		*i += 1;
+	}
+	if *i == start {
+		return Err(raw_json_error("expected a value in raw JSON fragment"));
+	}
+	Ok(&bytes[start..*i])
 }
 
 macro_rules! impl_write {
 	($name:ident, $ty:ty) => {
 		fn $name<W: Write + ?Sized>(&mut self, w: &mut W, value: $ty) -> Result {
-			CF.$name(&mut self.writer(w), value)
+			let mut counting = CountingWriter {
+				inner: self.writer(w),
+				count: 0,
+			};
+			CF.$name(&mut counting, value)?;
+			let count = counting.count;
+			self.current_column += count;
+			Ok(())
 		}
 	};
 	($name:ident) => {
 		fn $name<W: Write + ?Sized>(&mut self, w: &mut W) -> Result {
-			CF.$name(&mut self.writer(w))
+			let mut counting = CountingWriter {
+				inner: self.writer(w),
+				count: 0,
+			};
+			CF.$name(&mut counting)?;
+			let count = counting.count;
+			self.current_column += count;
+			Ok(())
 		}
 	};
 }
@@ -201,14 +497,23 @@ impl serde_json::ser::Formatter for Formatter<'_> {
 	impl_write!(end_string);
 	impl_write!(write_string_fragment, &str);
 	impl_write!(write_char_escape, serde_json::ser::CharEscape);
-	impl_write!(write_raw_fragment, &str);
+
+	fn write_raw_fragment<W: Write + ?Sized>(&mut self, w: &mut W, fragment: &str) -> Result {
+		if self.reindent_raw_values {
+			self.write_raw_json(w, fragment)
+		} else {
+			let mut buf = Vec::new();
+			CF.write_raw_fragment(&mut buf, fragment)?;
+			self.write_tracked(w, &buf)
+		}
+	}
 
 	fn begin_array<W: Write + ?Sized>(&mut self, w: &mut W) -> Result {
 		self.begin(w, b"[")
 	}
 
 	fn end_array<W: Write + ?Sized>(&mut self, w: &mut W) -> Result {
-		self.end(w, b"]")
+		self.end(w, b"]", ContainerKind::Array)
 	}
 
 	fn begin_array_value<W: Write + ?Sized>(&mut self, w: &mut W, first: bool) -> Result {
@@ -220,7 +525,7 @@ impl serde_json::ser::Formatter for Formatter<'_> {
 	}
 
 	fn end_object<W: Write + ?Sized>(&mut self, w: &mut W) -> Result {
-		self.end(w, b"}")
+		self.end(w, b"}", ContainerKind::Object)
 	}
 
 	fn begin_object_key<W: Write + ?Sized>(&mut self, w: &mut W, first: bool) -> Result {
@@ -228,7 +533,7 @@ impl serde_json::ser::Formatter for Formatter<'_> {
 	}
 
 	fn begin_object_value<W: Write + ?Sized>(&mut self, w: &mut W) -> Result {
-		self.writer(w).write_all(b": ")
+		self.write_tracked(w, b": ")
 	}
 }
 
@@ -250,6 +555,54 @@ pub fn to_writer<W: Write, T: Serialize + ?Sized>(
 	value.serialize(&mut ser)
 }
 
+/// Serialize the given data structure as JSON into the I/O stream, indented with `indent` if
+/// given, or compact (no whitespace) otherwise.
+///
+/// This lets a caller pick between interchange-compact and human-readable output at a single
+/// call site, without constructing a `Serializer` by hand.
+///
+/// # Errors
+///
+/// Serialization can fail if `T`'s implementation of `Serialize` decides to
+/// fail, or if `T` contains a map with non-string keys.
+#[inline]
+pub fn to_writer_with<'a, W: Write, T: Serialize + ?Sized>(
+	mut writer: W,
+	value: &T,
+	indent: impl Into<Option<&'a [u8]>>,
+) -> serde_json::Result<()> {
+	match indent.into() {
+		Some(indent) => {
+			let mut ser = Serializer::with_formatter(&mut writer, Formatter::with_indent(indent));
+			value.serialize(&mut ser)
+		}
+		None => {
+			let mut ser = Serializer::with_formatter(&mut writer, CF);
+			value.serialize(&mut ser)
+		}
+	}
+}
+
+/// Serialize the given data structure as pretty-printed JSON directly into a
+/// [`std::fmt::Write`] sink, such as a `String` or a `std::fmt::Formatter`.
+///
+/// This avoids the intermediate `Vec<u8>` that writing to a `String` via `to_string` goes
+/// through.
+///
+/// # Errors
+///
+/// Serialization can fail if `T`'s implementation of `Serialize` decides to
+/// fail, or if `T` contains a map with non-string keys.
+#[inline]
+pub fn to_fmt_writer<W: std::fmt::Write + ?Sized, T: Serialize + ?Sized>(
+	writer: &mut W,
+	value: &T,
+) -> serde_json::Result<()> {
+	let mut adapter = FmtWriter(writer);
+	let mut ser = Serializer::with_formatter(&mut adapter, Formatter::new());
+	value.serialize(&mut ser)
+}
+
 /// Serialize the given data structure as a pretty-printed JSON byte vector.
 ///
 /// # Errors
@@ -272,7 +625,130 @@ pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
 #[inline]
 pub fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String> {
 	let vec = to_vec(value)?;
-	// serde-json uses unsafe here, but I'll take the perf hit
-	let string = String::from_utf8(vec).unwrap();
+	// SAFETY: to_vec/to_writer only ever feed valid UTF-8 to the writer (see `to_writer`).
+	let string = unsafe { String::from_utf8_unchecked(vec) };
 	Ok(string)
 }
+
+/// Serialize the given data structure as a String of JSON, indented with `indent` if given, or
+/// compact (no whitespace) otherwise.
+///
+/// # Errors
+///
+/// Serialization can fail if `T`'s implementation of `Serialize` decides to
+/// fail, or if `T` contains a map with non-string keys.
+#[inline]
+pub fn to_string_with<'a, T: Serialize + ?Sized>(
+	value: &T,
+	indent: impl Into<Option<&'a [u8]>>,
+) -> Result<String> {
+	let mut writer = Vec::with_capacity(128);
+	to_writer_with(&mut writer, value, indent)?;
+	// SAFETY: to_writer_with only ever feeds valid UTF-8 to the writer (see `to_writer`).
+	let string = unsafe { String::from_utf8_unchecked(writer) };
+	Ok(string)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::Value;
+
+	#[test]
+	fn reindent_raw_values_preserves_key_order_and_number_literals() {
+		#[derive(Serialize)]
+		struct Wrapper<'a> {
+			#[serde(borrow)]
+			raw: &'a serde_json::value::RawValue,
+		}
+
+		let raw = serde_json::value::RawValue::from_string(
+			r#"{"z_first":1,"a_first":2,"pi":1.50,"big":1e10}"#.to_owned(),
+		)
+		.unwrap();
+		let wrapper = Wrapper { raw: &raw };
+
+		let formatter = Formatter::new().with_reindent_raw_values(true);
+		let mut writer = Vec::new();
+		let mut ser = Serializer::with_formatter(&mut writer, formatter);
+		wrapper.serialize(&mut ser).unwrap();
+		let s = String::from_utf8(writer).unwrap();
+
+		assert!(
+			s.find("z_first").unwrap() < s.find("a_first").unwrap(),
+			"object key order must be preserved, not alphabetized:\n{s}"
+		);
+		assert!(
+			s.contains("1.50") && s.contains("1e10"),
+			"numeric literals must be forwarded verbatim, not renormalized:\n{s}"
+		);
+	}
+
+	#[test]
+	fn max_width_fallback_round_trips() {
+		let value = serde_json::json!({
+			"a": "1234567890123456789012345",
+			"b": [1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+		});
+		let formatter = Formatter::new().with_max_width(30);
+		let mut writer = Vec::new();
+		let mut ser = Serializer::with_formatter(&mut writer, formatter);
+		value.serialize(&mut ser).unwrap();
+		let s = String::from_utf8(writer).unwrap();
+		let roundtripped: Value = serde_json::from_str(&s)
+			.unwrap_or_else(|e| panic!("output was not valid JSON: {e}\n{s}"));
+		assert_eq!(roundtripped, value);
+	}
+
+	#[test]
+	fn max_width_accounts_for_column_through_buffered_writes() {
+		let value = serde_json::json!({ "12345678901234567890": [1, 2, 3] });
+		let formatter = Formatter::new().with_max_width(30);
+		let mut writer = Vec::new();
+		let mut ser = Serializer::with_formatter(&mut writer, formatter);
+		value.serialize(&mut ser).unwrap();
+		let s = String::from_utf8(writer).unwrap();
+		assert!(
+			!s.contains("[ 1, 2, 3 ]"),
+			"nested array should not collapse once the preceding key pushes it past max_width:\n{s}"
+		);
+		let roundtripped: Value = serde_json::from_str(&s)
+			.unwrap_or_else(|e| panic!("output was not valid JSON: {e}\n{s}"));
+		assert_eq!(roundtripped, value);
+	}
+
+	#[test]
+	fn to_fmt_writer_matches_to_string() {
+		let value = serde_json::json!({ "a": 1, "b": [1, 2, 3] });
+		let mut s = String::new();
+		to_fmt_writer(&mut s, &value).unwrap();
+		assert_eq!(s, to_string(&value).unwrap());
+	}
+
+	#[test]
+	fn to_string_with_toggles_compact_and_indented_output() {
+		let value = serde_json::json!({ "a": 1, "b": 2 });
+		let compact = to_string_with(&value, None::<&[u8]>).unwrap();
+		assert_eq!(compact, r#"{"a":1,"b":2}"#);
+		let indented = to_string_with(&value, &b"  "[..]).unwrap();
+		assert_eq!(indented, to_string(&value).unwrap());
+	}
+
+	#[test]
+	fn with_policy_controls_which_containers_collapse() {
+		let value = serde_json::json!({ "a": [1, 2, 3], "b": { "c": 1 } });
+		let formatter = Formatter::new().with_policy(|ctx| ctx.kind == ContainerKind::Array);
+		let mut writer = Vec::new();
+		let mut ser = Serializer::with_formatter(&mut writer, formatter);
+		value.serialize(&mut ser).unwrap();
+		let s = String::from_utf8(writer).unwrap();
+		assert!(s.contains("[ 1, 2, 3 ]"), "arrays should still collapse:\n{s}");
+		assert!(
+			!s.contains("{ \"c\": 1 }"),
+			"objects should never collapse under this policy:\n{s}"
+		);
+		let roundtripped: Value = serde_json::from_str(&s)
+			.unwrap_or_else(|e| panic!("output was not valid JSON: {e}\n{s}"));
+		assert_eq!(roundtripped, value);
+	}
+}